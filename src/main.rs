@@ -5,9 +5,153 @@
 #![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
 use bevy::{asset::AssetMetaCheck, input::mouse::MouseMotion, prelude::*, window::CursorGrabMode};
+use bevy_ggrs::{ggrs, GgrsApp, GgrsPlugin, GgrsSchedule, PlayerInputs, Rollback, RollbackIdProvider};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_rapier3d::prelude::*;
-use rand::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use clap::Parser;
+use rand::{rngs::StdRng, prelude::*};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const STAR_SEED: u64 = 0x5eed_5eed_5eed_5eed;
+const ROLLBACK_FPS: u32 = 60;
+
+const INPUT_SHOOT: u8 = 1 << 0;
+const INPUT_INTERACT: u8 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable, Default)]
+#[repr(C)]
+pub struct BoxInput {
+    pub rotation_delta_y: f32,
+    pub rotation_delta_x: f32,
+    pub buttons: u8,
+    _padding: [u8; 3],
+}
+
+pub struct GGRSConfig;
+impl ggrs::Config for GGRSConfig {
+    type Input = BoxInput;
+    type State = u8;
+    type Address = SocketAddr;
+}
+
+#[derive(Parser, Resource, Clone)]
+pub struct NetworkArgs {
+    #[arg(long, default_value_t = 7000)]
+    pub local_port: u16,
+    #[arg(long, value_delimiter = ',')]
+    pub players: Vec<String>,
+    #[arg(long, value_delimiter = ',', default_value = "")]
+    pub spectators: Vec<String>,
+}
+
+pub fn build_ggrs_session(
+    args: &NetworkArgs,
+) -> ggrs::SessionBuilder<GGRSConfig> {
+    let mut builder = ggrs::SessionBuilder::<GGRSConfig>::new()
+        .with_num_players(args.players.len())
+        .with_input_delay(2);
+
+    for (i, player_addr) in args.players.iter().enumerate() {
+        let player_type = if player_addr == "localhost" {
+            ggrs::PlayerType::Local
+        } else {
+            ggrs::PlayerType::Remote(
+                player_addr.parse().expect("invalid player address"),
+            )
+        };
+        builder = builder
+            .add_player(player_type, i)
+            .expect("failed to add player");
+    }
+
+    for (i, spectator_addr) in args.spectators.iter().enumerate() {
+        if spectator_addr.is_empty() {
+            continue;
+        }
+        builder = builder
+            .add_player(
+                ggrs::PlayerType::Spectator(
+                    spectator_addr.parse().expect("invalid spectator address"),
+                ),
+                args.players.len() + i,
+            )
+            .expect("failed to add spectator");
+    }
+
+    builder
+}
+
+pub fn read_local_input(
+    mut commands: Commands,
+    local_players: Res<bevy_ggrs::LocalPlayers>,
+    camera_controller_state: Res<CameraControllerState>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    mut shoot_event_reader: EventReader<ShootBulletEvent>,
+    keyboard: Res<Input<KeyCode>>,
+) {
+    let mut rotation_delta_y = 0.0;
+    let mut rotation_delta_x = 0.0;
+    if camera_controller_state.active {
+        for event in mouse_motion_events.read() {
+            rotation_delta_y -= event.delta.x * camera_controller_state.mouse_speed;
+            rotation_delta_x += event.delta.y * camera_controller_state.mouse_speed;
+        }
+    }
+
+    let mut buttons = 0;
+    if shoot_event_reader.read().next().is_some() {
+        buttons |= INPUT_SHOOT;
+    }
+    if keyboard.just_pressed(INTERACT_KEY) {
+        buttons |= INPUT_INTERACT;
+    }
+
+    let mut local_inputs = std::collections::HashMap::new();
+    for handle in &local_players.0 {
+        local_inputs.insert(
+            *handle,
+            BoxInput {
+                rotation_delta_y,
+                rotation_delta_x,
+                buttons,
+                _padding: [0; 3],
+            },
+        );
+    }
+    commands.insert_resource(bevy_ggrs::LocalInputs::<GGRSConfig>(local_inputs));
+}
+
+#[derive(Component)]
+pub struct NetworkHandle(pub usize);
+
+pub fn rollback_shoot(
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    player_query: Query<(Entity, &NetworkHandle)>,
+    mut bullet_event_writer: EventWriter<ShootBulletEvent>,
+) {
+    for (entity, handle) in player_query.iter() {
+        let (input, _) = inputs[handle.0];
+        if input.buttons & INPUT_SHOOT != 0 {
+            bullet_event_writer.send(ShootBulletEvent { shooter: entity });
+        }
+    }
+}
+
+pub fn rollback_rotation(
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    mut query: Query<(&mut Transform, &NetworkHandle)>,
+) {
+    for (mut transform, handle) in query.iter_mut() {
+        let (input, _) = inputs[handle.0];
+        let delta =
+            Quat::from_euler(EulerRot::YXZ, input.rotation_delta_y, input.rotation_delta_x, 0.0);
+        transform.rotation *= delta;
+    }
+}
 
 #[derive(Resource)]
 pub struct BulletAssets {
@@ -55,6 +199,23 @@ pub struct Star;
 
 #[derive(Component)]
 pub struct Player;
+
+#[derive(Component, Clone)]
+pub struct Pilot {
+    pub vehicle: Entity,
+    pub handle: usize,
+    pub was_interacting: bool,
+}
+
+#[derive(Event)]
+pub struct VehicleEnterExitEvent {
+    pub driver: Entity,
+    pub vehicle: Entity,
+}
+
+pub const INTERACT_DISTANCE: f32 = 5.0;
+pub const INTERACT_KEY: KeyCode = KeyCode::F;
+
 #[derive(Component)]
 pub struct Ship {
     pub speed: f32,
@@ -63,8 +224,106 @@ pub struct Ship {
 #[derive(Component)]
 pub struct Enemy;
 
-#[derive(Component)]
-pub struct Bullet;
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnemyArchetype {
+    pub speed: f32,
+    pub bullet_spawn_distance: f32,
+    pub fire_cadence: f32,
+    pub script_path: String,
+}
+
+#[derive(Resource, Deref)]
+pub struct EnemyArchetypes(pub HashMap<String, EnemyArchetype>);
+
+pub fn load_enemy_archetypes() -> EnemyArchetypes {
+    let contents = std::fs::read_to_string("assets/enemies.toml")
+        .expect("failed to read assets/enemies.toml");
+    EnemyArchetypes(toml::from_str(&contents).expect("invalid assets/enemies.toml"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiDirective {
+    Pursue,
+    Strafe,
+    Flee,
+    Fire,
+}
+impl AiDirective {
+    pub fn from_script_output(value: &str) -> Self {
+        match value {
+            "strafe" => AiDirective::Strafe,
+            "flee" => AiDirective::Flee,
+            "fire" => AiDirective::Fire,
+            _ => AiDirective::Pursue,
+        }
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct EnemyAi {
+    pub script: rhai::AST,
+    pub fire_timer: Timer,
+}
+impl EnemyAi {
+    pub fn from_archetype(archetype: &EnemyArchetype) -> Self {
+        let engine = rhai::Engine::new();
+        let script = engine
+            .compile_file(archetype.script_path.clone().into())
+            .unwrap_or_else(|e| panic!("invalid AI script {}: {e}", archetype.script_path));
+        Self {
+            script,
+            fire_timer: Timer::from_seconds(archetype.fire_cadence, TimerMode::Repeating),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Caliber {
+    Light,
+    Standard,
+    Heavy,
+}
+impl Caliber {
+    pub fn damage(&self) -> f32 {
+        match self {
+            Caliber::Light => 10.0,
+            Caliber::Standard => 25.0,
+            Caliber::Heavy => 50.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BulletHit {
+    pub entity: Entity,
+    pub position: Vec3,
+}
+
+#[derive(Component, Clone)]
+pub struct BulletMarker {
+    pub shooter: Entity,
+    pub caliber: Caliber,
+    pub starting_point: Vec3,
+    pub current_velocity: Vec3,
+    pub hits: Vec<BulletHit>,
+}
+
+#[derive(Component, Clone)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+#[derive(Event)]
+pub struct ShipDestroyedEvent {
+    pub entity: Entity,
+    pub killer: Entity,
+}
 
 #[derive(Component)]
 pub struct DelayedDespawn {
@@ -94,6 +353,7 @@ pub struct ShipBundle {
     scene_bundle: SceneBundle,
     name: Name,
     ship: Ship,
+    health: Health,
     collider: Collider,
     rigid_body: RigidBody,
     velocity: Velocity,
@@ -106,11 +366,12 @@ pub struct PlayerBundle {
     camera_focus: CameraFocus,
 }
 impl PlayerBundle {
-    pub fn new(asset_server: &AssetServer) -> Self {
+    pub fn new(asset_server: &AssetServer, transform: Transform) -> Self {
         Self {
             ship_bundle: ShipBundle {
                 scene_bundle: SceneBundle {
                     scene: asset_server.load("ship.glb#Scene0"),
+                    transform,
                     ..Default::default()
                 },
                 name: Name::new("Ship"),
@@ -118,6 +379,7 @@ impl PlayerBundle {
                     speed: 10.0,
                     bullet_spawn_distance: 2.0,
                 },
+                health: Health::new(100.0),
                 collider: Collider::cuboid(1.0, 0.4, 1.0),
                 rigid_body: RigidBody::Dynamic,
                 velocity: Velocity::default(),
@@ -132,9 +394,14 @@ impl PlayerBundle {
 pub struct EnemyBundle {
     ship_bundle: ShipBundle,
     enemy: Enemy,
+    enemy_ai: EnemyAi,
 }
 impl EnemyBundle {
-    pub fn new(asset_server: &AssetServer, transform: Transform) -> Self {
+    pub fn new(
+        asset_server: &AssetServer,
+        transform: Transform,
+        archetype: &EnemyArchetype,
+    ) -> Self {
         Self {
             ship_bundle: ShipBundle {
                 scene_bundle: SceneBundle {
@@ -144,14 +411,16 @@ impl EnemyBundle {
                 },
                 name: Name::new("Enemy"),
                 ship: Ship {
-                    speed: 5.0,
-                    bullet_spawn_distance: 2.0,
+                    speed: archetype.speed,
+                    bullet_spawn_distance: archetype.bullet_spawn_distance,
                 },
+                health: Health::new(100.0),
                 collider: Collider::cuboid(1.0, 0.4, 1.0),
                 rigid_body: RigidBody::Dynamic,
                 velocity: Velocity::default(),
                 active_events: ActiveEvents::COLLISION_EVENTS,
             },
+            enemy_ai: EnemyAi::from_archetype(archetype),
             enemy: Enemy,
         }
     }
@@ -160,16 +429,22 @@ impl EnemyBundle {
 #[derive(Bundle)]
 pub struct BulletBundle {
     pub pbr_bundle: PbrBundle,
-    pub bullet: Bullet,
+    pub bullet: BulletMarker,
     pub collider: Collider,
+    pub sensor: Sensor,
     pub rigid_body: RigidBody,
-    pub velocity: Velocity,
     pub name: Name,
     pub delayed_despawn: DelayedDespawn,
-    pub active_events: ActiveEvents,
 }
 impl BulletBundle {
-    pub fn new(bullet_assets: &BulletAssets, direction: Vec3, transform: Transform) -> Self {
+    pub fn new(
+        bullet_assets: &BulletAssets,
+        shooter: Entity,
+        caliber: Caliber,
+        direction: Vec3,
+        transform: Transform,
+    ) -> Self {
+        let current_velocity = direction * 100.0;
         Self {
             pbr_bundle: PbrBundle {
                 mesh: bullet_assets.mesh.clone(),
@@ -177,18 +452,30 @@ impl BulletBundle {
                 transform,
                 ..Default::default()
             },
-            bullet: Bullet,
+            bullet: BulletMarker {
+                shooter,
+                caliber,
+                starting_point: transform.translation,
+                current_velocity,
+                hits: Vec::new(),
+            },
             collider: Collider::ball(0.1),
-            rigid_body: RigidBody::Dynamic,
-            velocity: Velocity::linear(direction * 100.0),
+            sensor: Sensor,
+            rigid_body: RigidBody::KinematicPositionBased,
             name: Name::new("Bullet"),
             delayed_despawn: DelayedDespawn::new(5.0),
-            active_events: ActiveEvents::COLLISION_EVENTS,
         }
     }
 }
 
 fn main() {
+    let mut network_args = NetworkArgs::parse();
+    if network_args.players.is_empty() {
+        // No peers given: play locally with a single local player so the
+        // rollback schedule still drives gameplay the same way it does online.
+        network_args.players = vec!["localhost".to_string()];
+    }
+
     let mut app = App::new();
     app.insert_resource(AssetMetaCheck::Never)
         .add_plugins(DefaultPlugins)
@@ -199,26 +486,58 @@ fn main() {
             ..Default::default()
         })
         .add_event::<ShootBulletEvent>()
+        .add_event::<ShipDestroyedEvent>()
+        .add_event::<VehicleEnterExitEvent>()
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugins(RapierDebugRenderPlugin::default());
+        .add_plugins(RapierDebugRenderPlugin::default())
+        .add_plugins(GgrsPlugin::<GGRSConfig>::default());
 
     if cfg!(debug_assertions) {
         app.add_plugins(WorldInspectorPlugin::new());
     }
 
+    let socket = ggrs::UdpNonBlockingSocket::bind_to_port(network_args.local_port)
+        .expect("failed to bind local UDP socket");
+    let session = build_ggrs_session(&network_args)
+        .start_p2p_session(socket)
+        .expect("failed to start GGRS session");
+    app.insert_resource(bevy_ggrs::Session::P2P(session));
+    app.insert_resource(network_args);
+
+    app.set_rollback_schedule_fps(ROLLBACK_FPS)
+        .rollback_component_with_clone::<Transform>()
+        .rollback_component_with_clone::<Velocity>()
+        .rollback_component_with_clone::<Pilot>()
+        .rollback_component_with_clone::<Health>()
+        .rollback_component_with_clone::<BulletMarker>()
+        .rollback_component_with_clone::<EnemyAi>()
+        .rollback_component_with_clone::<Tunneling>()
+        .add_systems(bevy_ggrs::ReadInputs, read_local_input)
+        .add_systems(
+            GgrsSchedule,
+            (
+                rollback_rotation,
+                rollback_vehicle_enter_exit,
+                ship_velocity_controller,
+                enemy_ai,
+                detect_tunneling,
+                rollback_shoot,
+                spawn_bullet,
+                bullet_raycast,
+                apply_bullet_damage,
+                delayed_despawn,
+            )
+                .chain(),
+        );
+
     app.add_systems(Startup, (setup, setup_stars))
         .add_systems(
             Update,
             (
                 camera_transform_update,
                 camera_controller.pipe(error_handler),
-                ship_velocity_controller,
                 respawn_stars.pipe(error_handler),
-                player_rotation_controller.pipe(error_handler),
-                spawn_bullet,
                 autoshoot.pipe(error_handler),
-                delayed_despawn,
-                bullet_collision,
                 collision_logger,
             ),
         )
@@ -230,6 +549,8 @@ pub fn setup(
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rollback_ids: ResMut<RollbackIdProvider>,
+    network_args: Res<NetworkArgs>,
 ) {
     commands.spawn((
         Camera3dBundle {
@@ -242,11 +563,46 @@ pub fn setup(
         ActiveCamera::default(),
     ));
 
-    commands.spawn(PlayerBundle::new(&asset_server));
-    commands.spawn(EnemyBundle::new(
-        &asset_server,
-        Transform::from_xyz(0.0, 0.0, -50.0),
+    let local_player_index = network_args
+        .players
+        .iter()
+        .position(|addr| addr == "localhost")
+        .unwrap_or(0);
+
+    for i in 0..network_args.players.len() {
+        let mut ship = commands.spawn((
+            PlayerBundle::new(&asset_server, Transform::from_xyz(i as f32 * 5.0, 0.0, 0.0)),
+            Rollback::new(rollback_ids.next_id()),
+            NetworkHandle(i),
+        ));
+        if i != local_player_index {
+            ship.remove::<Player>();
+            ship.remove::<CameraFocus>();
+        }
+        let vehicle = ship.id();
+        commands.spawn((
+            Pilot {
+                vehicle,
+                handle: i,
+                was_interacting: false,
+            },
+            Rollback::new(rollback_ids.next_id()),
+        ));
+    }
+    let enemy_archetypes = load_enemy_archetypes();
+    let grunt_archetype = enemy_archetypes
+        .get("grunt")
+        .cloned()
+        .expect("assets/enemies.toml is missing the grunt archetype");
+    commands.spawn((
+        EnemyBundle::new(
+            &asset_server,
+            Transform::from_xyz(0.0, 0.0, -50.0),
+            &grunt_archetype,
+        ),
+        Rollback::new(rollback_ids.next_id()),
     ));
+    commands.insert_resource(enemy_archetypes);
     commands.insert_resource(AmbientLight {
         color: Color::ALICE_BLUE,
         brightness: 0.8,
@@ -300,7 +656,7 @@ pub fn setup_stars(
             InheritedVisibility::default(),
         ))
         .with_children(move |stars| {
-            let mut rng = rand::thread_rng();
+            let mut rng = StdRng::seed_from_u64(STAR_SEED);
             for _ in 0..1000 {
                 let phi = rng.gen_range(0.0..std::f32::consts::PI * 2.0);
                 let theta = rng.gen_range(0.0..std::f32::consts::PI * 2.0);
@@ -399,29 +755,20 @@ pub fn camera_controller(
     Ok(())
 }
 
-pub fn ship_velocity_controller(mut player_query: Query<(&mut Velocity, &Transform, &Ship)>) {
+pub fn ship_velocity_controller(
+    mut player_query: Query<(&mut Velocity, &Transform, &Ship), Without<EnemyAi>>,
+) {
     for (mut velocity, transform, ship) in player_query.iter_mut() {
         velocity.linvel = transform.rotation * Vec3::new(0.0, 0.0, -ship.speed);
     }
 }
 
-pub fn player_rotation_controller(
-    mut player_query: Query<&mut Transform, With<Player>>,
-    camera_query: Query<&Transform, (With<ActiveCamera>, Without<Player>)>,
-) -> anyhow::Result<()> {
-    let mut player_transform = player_query.get_single_mut()?;
-    let camera_transform = camera_query.get_single()?;
-    player_transform.rotation =
-        player_transform.rotation + (camera_transform.rotation - player_transform.rotation) * 0.2;
-
-    Ok(())
-}
-
 pub fn spawn_bullet(
     mut commands: Commands,
     mut bullet_event_reader: EventReader<ShootBulletEvent>,
     ship_query: Query<(&Transform, &Ship)>,
     bullet_assets: Res<BulletAssets>,
+    mut rollback_ids: ResMut<RollbackIdProvider>,
 ) {
     for bullet_event in bullet_event_reader.read() {
         if let Ok((transform, ship)) = ship_query.get(bullet_event.shooter) {
@@ -429,10 +776,12 @@ pub fn spawn_bullet(
             let bullet_spawn_offset = forward_vector * ship.bullet_spawn_distance;
             let bullet = BulletBundle::new(
                 bullet_assets.as_ref(),
+                bullet_event.shooter,
+                Caliber::Standard,
                 forward_vector,
                 Transform::from_translation(transform.translation + bullet_spawn_offset),
             );
-            commands.spawn(bullet);
+            commands.spawn((bullet, Rollback::new(rollback_ids.next_id())));
         }
     }
 }
@@ -457,6 +806,120 @@ pub fn autoshoot(
     Ok(())
 }
 
+pub fn rollback_vehicle_enter_exit(
+    mut commands: Commands,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    mut pilot_query: Query<(Entity, &mut Pilot)>,
+    ship_query: Query<(Entity, &Transform), With<Ship>>,
+    network_handle_query: Query<&NetworkHandle>,
+    mut vehicle_events: EventWriter<VehicleEnterExitEvent>,
+) {
+    for (pilot_entity, mut pilot) in pilot_query.iter_mut() {
+        let (input, _) = inputs[pilot.handle];
+        let interacting = input.buttons & INPUT_INTERACT != 0;
+        let just_pressed = interacting && !pilot.was_interacting;
+        pilot.was_interacting = interacting;
+        if !just_pressed {
+            continue;
+        }
+
+        let Ok((_, current_transform)) = ship_query.get(pilot.vehicle) else {
+            continue;
+        };
+        let current_position = current_transform.translation;
+
+        let nearest_vehicle = ship_query
+            .iter()
+            .filter(|(entity, _)| *entity != pilot.vehicle)
+            .map(|(entity, transform)| (entity, transform.translation.distance(current_position)))
+            .filter(|(_, distance)| *distance <= INTERACT_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+
+        let Some((new_vehicle, _)) = nearest_vehicle else {
+            continue;
+        };
+
+        let network_handle = network_handle_query.get(pilot.vehicle).ok().map(|h| h.0);
+
+        commands.entity(pilot.vehicle).remove::<Player>();
+        commands.entity(pilot.vehicle).remove::<CameraFocus>();
+        commands.entity(pilot.vehicle).remove::<NetworkHandle>();
+        commands.entity(new_vehicle).remove::<EnemyAi>();
+        commands.entity(new_vehicle).insert((Player, CameraFocus));
+        if let Some(handle) = network_handle {
+            commands.entity(new_vehicle).insert(NetworkHandle(handle));
+        }
+        pilot.vehicle = new_vehicle;
+
+        vehicle_events.send(VehicleEnterExitEvent {
+            driver: pilot_entity,
+            vehicle: new_vehicle,
+        });
+    }
+}
+
+pub fn enemy_ai(
+    mut bullet_event_writer: EventWriter<ShootBulletEvent>,
+    mut enemy_query: Query<
+        (Entity, &mut Transform, &mut Velocity, &mut EnemyAi, &Ship),
+        (With<Enemy>, Without<Player>),
+    >,
+    player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
+) {
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let engine = rhai::Engine::new();
+
+    for (enemy_entity, mut transform, mut velocity, mut ai, ship) in enemy_query.iter_mut() {
+        let distance = transform.translation.distance(player_transform.translation);
+        let mut scope = rhai::Scope::new();
+        scope.push("distance", distance as f64);
+        scope.push("enemy_x", transform.translation.x as f64);
+        scope.push("enemy_y", transform.translation.y as f64);
+        scope.push("enemy_z", transform.translation.z as f64);
+        scope.push("player_x", player_transform.translation.x as f64);
+        scope.push("player_y", player_transform.translation.y as f64);
+        scope.push("player_z", player_transform.translation.z as f64);
+
+        let directive = engine
+            .eval_ast_with_scope::<String>(&mut scope, &ai.script)
+            .map(|value| AiDirective::from_script_output(&value))
+            .unwrap_or(AiDirective::Pursue);
+
+        match directive {
+            AiDirective::Pursue => {
+                let to_player =
+                    (player_transform.translation - transform.translation).normalize_or_zero();
+                transform.look_to(to_player, Vec3::Y);
+                velocity.linvel = transform.rotation * Vec3::new(0.0, 0.0, -ship.speed);
+            }
+            AiDirective::Strafe => {
+                let to_player =
+                    (player_transform.translation - transform.translation).normalize_or_zero();
+                velocity.linvel = to_player.cross(Vec3::Y) * ship.speed;
+            }
+            AiDirective::Flee => {
+                let away_from_player =
+                    (transform.translation - player_transform.translation).normalize_or_zero();
+                transform.look_to(away_from_player, Vec3::Y);
+                velocity.linvel = transform.rotation * Vec3::new(0.0, 0.0, -ship.speed);
+            }
+            AiDirective::Fire => {
+                velocity.linvel = Vec3::ZERO;
+                // Ticked by a fixed rollback-tick duration (not Res<Time>) so the
+                // timer advances identically on every peer during resimulation.
+                let rollback_tick = Duration::from_secs_f64(1.0 / ROLLBACK_FPS as f64);
+                if ai.fire_timer.tick(rollback_tick).just_finished() {
+                    bullet_event_writer.send(ShootBulletEvent {
+                        shooter: enemy_entity,
+                    });
+                }
+            }
+        }
+    }
+}
+
 pub fn delayed_despawn(
     mut commands: Commands,
     mut delayed_despawn_query: Query<(Entity, &mut DelayedDespawn)>,
@@ -469,33 +932,143 @@ pub fn delayed_despawn(
     }
 }
 
-pub fn bullet_collision(
+#[derive(Component, Clone, Default)]
+pub struct Tunneling {
+    pub frames: usize,
+    pub dir: Vec3,
+}
+
+fn rollback_delta_seconds() -> f32 {
+    Duration::from_secs_f64(1.0 / ROLLBACK_FPS as f64).as_secs_f32()
+}
+
+fn smallest_half_extent(collider: &Collider) -> f32 {
+    if let Some(cuboid) = collider.as_cuboid() {
+        let half_extents = cuboid.half_extents();
+        half_extents.x.min(half_extents.y).min(half_extents.z)
+    } else if let Some(ball) = collider.as_ball() {
+        ball.radius()
+    } else {
+        0.0
+    }
+}
+
+pub fn detect_tunneling(
     mut commands: Commands,
-    mut collision_events: EventReader<CollisionEvent>,
-    bullet_query: Query<&Bullet>,
+    rapier_context: Res<RapierContext>,
+    mut body_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &Velocity,
+            &Collider,
+            Option<&mut Tunneling>,
+        ),
+        With<RigidBody>,
+    >,
 ) {
-    let bullet_collusions = collision_events
-        .read()
-        .filter_map(|event| {
-            if let CollisionEvent::Started(entity1, entity2, _) = event {
-                Some((entity1, entity2))
-            } else {
-                None
+    for (entity, mut transform, velocity, collider, tunneling) in body_query.iter_mut() {
+        let displacement = velocity.linvel * rollback_delta_seconds();
+        let distance = displacement.length();
+        if distance <= smallest_half_extent(collider) {
+            if let Some(mut tunneling) = tunneling {
+                tunneling.frames = 0;
             }
-        })
-        .filter_map(|(entity1, entity2)| {
-            if bullet_query.contains(*entity1) {
-                Some((entity1, entity2))
-            } else if bullet_query.contains(*entity2) {
-                Some((entity2, entity1))
-            } else {
-                None
+            continue;
+        }
+        let direction = displacement / distance;
+        let prev_position = transform.translation - displacement;
+
+        let filter = QueryFilter::default()
+            .exclude_collider(entity)
+            .exclude_sensors();
+        if let Some((_, hit)) = rapier_context.cast_shape(
+            prev_position,
+            transform.rotation,
+            direction,
+            collider,
+            distance,
+            filter,
+        ) {
+            transform.translation = prev_position + direction * (hit.toi - 0.01).max(0.0);
+            match tunneling {
+                Some(mut tunneling) => {
+                    tunneling.frames += 1;
+                    tunneling.dir = direction;
+                }
+                None => {
+                    commands.entity(entity).insert(Tunneling {
+                        frames: 1,
+                        dir: direction,
+                    });
+                }
             }
-        });
+        } else if let Some(mut tunneling) = tunneling {
+            tunneling.frames = 0;
+        }
+    }
+}
+
+pub fn bullet_raycast(
+    rapier_context: Res<RapierContext>,
+    mut bullet_query: Query<(Entity, &mut Transform, &mut BulletMarker)>,
+) {
+    for (bullet_entity, mut transform, mut bullet) in bullet_query.iter_mut() {
+        if !bullet.hits.is_empty() {
+            continue;
+        }
+
+        let prev_position = transform.translation;
+        let displacement = bullet.current_velocity * rollback_delta_seconds();
+        let distance = displacement.length();
+        if distance <= 0.0 {
+            continue;
+        }
+        let direction = displacement / distance;
+
+        let filter = QueryFilter::default()
+            .exclude_collider(bullet_entity)
+            .exclude_sensors();
+        if let Some((hit_entity, toi)) =
+            rapier_context.cast_ray(prev_position, direction, distance, true, filter)
+        {
+            let hit_position = prev_position + direction * toi;
+            bullet.hits.push(BulletHit {
+                entity: hit_entity,
+                position: hit_position,
+            });
+            transform.translation = hit_position;
+        } else {
+            transform.translation = prev_position + displacement;
+        }
+    }
+}
+
+pub fn apply_bullet_damage(
+    mut commands: Commands,
+    bullet_query: Query<(Entity, &BulletMarker)>,
+    mut health_query: Query<&mut Health>,
+    mut ship_destroyed_events: EventWriter<ShipDestroyedEvent>,
+) {
+    for (bullet_entity, bullet) in bullet_query.iter() {
+        if bullet.hits.is_empty() {
+            continue;
+        }
+
+        for hit in &bullet.hits {
+            if let Ok(mut health) = health_query.get_mut(hit.entity) {
+                health.current -= bullet.caliber.damage();
+                if health.current <= 0.0 {
+                    commands.entity(hit.entity).despawn_recursive();
+                    ship_destroyed_events.send(ShipDestroyedEvent {
+                        entity: hit.entity,
+                        killer: bullet.shooter,
+                    });
+                }
+            }
+        }
 
-    for (bullet, other_entity) in bullet_collusions {
-        commands.entity(*bullet).despawn();
-        commands.entity(*other_entity).despawn_recursive();
+        commands.entity(bullet_entity).despawn();
     }
 }
 